@@ -1,16 +1,48 @@
 use structopt::StructOpt;
-use handflip_core::http::HttpProxy;
-use anyhow::Result;
+use handflip_core::http::{HttpProxy, ProxyProtocolVersion};
+use anyhow::{Result, bail};
+use std::time::Duration;
 
 fn main() -> Result<()> {
     env_logger::init();
     let opts = Opts::from_args();
     let addr = format!("{}:{}", opts.host, opts.port);
-    let http_proxy = if let Some(socks5) = opts.socks5 {
+    let mut http_proxy = if let Some(socks5) = opts.socks5 {
         HttpProxy::via_socks5(socks5)
+    } else if let Some(kcp) = opts.kcp {
+        HttpProxy::via_kcp(kcp)
     } else {
         HttpProxy::direct()
     };
+    if let Some(proxy_protocol) = opts.proxy_protocol {
+        let version = match proxy_protocol.as_str() {
+            "v1" => ProxyProtocolVersion::V1,
+            "v2" => ProxyProtocolVersion::V2,
+            other => bail!("invalid proxy-protocol version {}, expect v1 or v2", other),
+        };
+        http_proxy = http_proxy.with_proxy_protocol(version);
+    }
+    if let Some(socks5_auth) = opts.socks5_auth {
+        let (username, password) = match socks5_auth.split_once(':') {
+            Some((username, password)) => (username.to_owned(), password.to_owned()),
+            None => bail!("invalid socks5-auth {}, expect user:pass", socks5_auth),
+        };
+        http_proxy = http_proxy.with_socks5_auth(username, password);
+    }
+    if opts.socks5_remote_resolve {
+        http_proxy = http_proxy.with_socks5_remote_resolve();
+    }
+    if let Some(router_config) = opts.router_config {
+        http_proxy = http_proxy.with_router_config(router_config)?;
+    }
+    if let (Some(cert), Some(key)) = (opts.tls_cert.clone(), opts.tls_key.clone()) {
+        http_proxy = http_proxy.with_tls_files(cert, key)?;
+    } else if opts.tls_cert.is_some() || opts.tls_key.is_some() {
+        bail!("--tls-cert and --tls-key must be specified together");
+    } else if opts.tls {
+        http_proxy = http_proxy.with_generated_tls()?;
+    }
+    http_proxy = http_proxy.with_pool(opts.pool_max_idle, Duration::from_secs(opts.pool_idle_timeout));
     log::debug!("listening at {}", addr);
     smol::block_on(http_proxy.bind(addr))?;
     Ok(())
@@ -24,4 +56,24 @@ pub struct Opts {
     pub port: u16,
     #[structopt(short, long, help = "specify socks5 proxy address, e.g. 127.0.0.1:1080")]
     pub socks5: Option<String>,
+    #[structopt(long, help = "send a PROXY protocol header to the upstream carrying the real client address, v1 or v2")]
+    pub proxy_protocol: Option<String>,
+    #[structopt(long, help = "authenticate to the socks5 proxy with username/password, e.g. user:pass")]
+    pub socks5_auth: Option<String>,
+    #[structopt(long, help = "let the socks5 proxy resolve the target hostname instead of resolving it locally")]
+    pub socks5_remote_resolve: bool,
+    #[structopt(long, parse(from_os_str), help = "load per-host routing rules (ban/echo/proxy) from this config file")]
+    pub router_config: Option<std::path::PathBuf>,
+    #[structopt(long, help = "carry the upstream connection over KCP via a relay at this address, e.g. 127.0.0.1:2080")]
+    pub kcp: Option<String>,
+    #[structopt(long, parse(from_os_str), help = "PEM certificate chain for the TLS listener, requires --tls-key")]
+    pub tls_cert: Option<std::path::PathBuf>,
+    #[structopt(long, parse(from_os_str), help = "PEM private key for the TLS listener, requires --tls-cert")]
+    pub tls_key: Option<std::path::PathBuf>,
+    #[structopt(long, help = "terminate TLS on the listener using a self-signed certificate generated at startup")]
+    pub tls: bool,
+    #[structopt(long, default_value = "8", help = "max idle upstream connections kept pooled per host:port")]
+    pub pool_max_idle: usize,
+    #[structopt(long, default_value = "60", help = "seconds an idle pooled upstream connection stays eligible for reuse")]
+    pub pool_idle_timeout: u64,
 }
@@ -3,27 +3,110 @@ use crate::error::{Result, Error};
 use async_h1::{server, client};
 use futures::{io, future, StreamExt, AsyncWriteExt};
 use http_types::{Request, Response, StatusCode, Method};
+use std::net::SocketAddr;
+use std::path::Path;
 use crate::socks5;
+use crate::router::{Router, Upstream};
+use crate::stream::{ClientStream, UpstreamStream};
+use crate::tls::TlsListener;
+use crate::pool::Pool;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct HttpProxy {
-    transport: Transport,
+    router: Router,
+    proxy_protocol: Option<ProxyProtocolVersion>,
+    tls: Option<TlsListener>,
+    pool: Option<Pool>,
 }
 
 impl HttpProxy {
 
     pub fn direct() -> Self {
         Self{
-            transport: Transport::Direct,
+            router: Router::new(Upstream::Proxy(Transport::Direct)),
+            proxy_protocol: None,
+            tls: None,
+            pool: None,
         }
     }
 
     pub fn via_socks5(socks5: String) -> Self {
         Self{
-            transport: Transport::Socks5(socks5),
+            router: Router::new(Upstream::Proxy(Transport::Socks5{ addr: socks5, auth: None, remote_resolve: false })),
+            proxy_protocol: None,
+            tls: None,
+            pool: None,
         }
     }
 
+    /// carry the upstream connection over KCP via a relay listening at `kcp`,
+    /// instead of raw TCP
+    pub fn via_kcp(kcp: String) -> Self {
+        Self{
+            router: Router::new(Upstream::Proxy(Transport::Kcp(kcp))),
+            proxy_protocol: None,
+            tls: None,
+            pool: None,
+        }
+    }
+
+    /// load per-host routing rules (ban/echo/proxy) from a config file, see
+    /// [`crate::router::Router::load_rules`] for the file format
+    pub fn with_router_config(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        self.router = self.router.load_rules(path)?;
+        Ok(self)
+    }
+
+    /// authenticate to the SOCKS5 proxy with a username/password (RFC 1929).
+    /// has no effect unless the default upstream is [`Transport::Socks5`]
+    pub fn with_socks5_auth(mut self, username: String, password: String) -> Self {
+        if let Upstream::Proxy(Transport::Socks5{ auth, .. }) = &mut self.router.default {
+            *auth = Some(socks5::client::Credentials{ username, password });
+        }
+        self
+    }
+
+    /// forward the target hostname to the SOCKS5 proxy and let it perform DNS
+    /// resolution, instead of resolving locally. has no effect unless the
+    /// default upstream is [`Transport::Socks5`]
+    pub fn with_socks5_remote_resolve(mut self) -> Self {
+        if let Upstream::Proxy(Transport::Socks5{ remote_resolve, .. }) = &mut self.router.default {
+            *remote_resolve = true;
+        }
+        self
+    }
+
+    /// enable sending a PROXY protocol header to the upstream right after connecting,
+    /// so origin servers see the real client address instead of the proxy's
+    pub fn with_proxy_protocol(mut self, version: ProxyProtocolVersion) -> Self {
+        self.proxy_protocol = Some(version);
+        self
+    }
+
+    /// terminate TLS on the listener using a PEM certificate chain and private
+    /// key loaded from disk, instead of speaking plaintext HTTP to clients
+    pub fn with_tls_files(mut self, cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> Result<Self> {
+        self.tls = Some(TlsListener::from_files(cert_path, key_path)?);
+        Ok(self)
+    }
+
+    /// terminate TLS on the listener using a self-signed certificate generated
+    /// at startup, for local testing when no real certificate is available
+    pub fn with_generated_tls(mut self) -> Result<Self> {
+        self.tls = Some(TlsListener::with_generated_cert()?);
+        Ok(self)
+    }
+
+    /// pool idle upstream TCP connections per `host:port`, so a later request
+    /// to the same destination can skip a fresh handshake. `max_idle` caps how
+    /// many idle connections are kept per destination, `idle_timeout` bounds
+    /// how long one stays eligible for reuse
+    pub fn with_pool(mut self, max_idle: usize, idle_timeout: Duration) -> Self {
+        self.pool = Some(Pool::new(max_idle, idle_timeout));
+        self
+    }
+
     /// bind to given address
     pub async fn bind(&self, addr: impl AsyncToSocketAddrs) -> Result<()> {
         let listener = TcpListener::bind(addr).await?;
@@ -43,26 +126,45 @@ impl HttpProxy {
     }
 
     async fn handle(&self, conn: TcpStream) -> Result<()> {
+        let conn = match &self.tls {
+            Some(tls) => ClientStream::Tls(tls.accept(conn).await?),
+            None => ClientStream::Plain(conn),
+        };
         if let Some(req) = server::decode(conn.clone()).await? {
             self.handle_request(conn, req).await?;
         }
         Ok(())
     }
 
-    async fn handle_request(&self, conn: TcpStream, req: Request) -> Result<()> {
+    async fn handle_request(&self, mut conn: ClientStream, req: Request) -> Result<()> {
         log::debug!("req from {:?}={:#?}", conn.peer_addr(), req);
-        match req.method() {
-            Method::Connect => self.handle_connect_request(conn, req).await?,
-            _ => self.handle_other_request(conn, req).await?,
+        let (host, _) = host_port_from_req(&req)?;
+        match self.router.resolve(host) {
+            Upstream::Ban => {
+                log::debug!("host {} is banned, refusing request", host);
+                let resp = Response::new(StatusCode::Forbidden);
+                let encoder = server::Encoder::new(resp, req.method());
+                io::copy(encoder, &mut conn).await?;
+                Ok(())
+            }
+            Upstream::Echo => {
+                log::debug!("host {} routed to echo", host);
+                echo(conn, req).await
+            }
+            Upstream::Proxy(transport) => {
+                match req.method() {
+                    Method::Connect => self.handle_connect_request(conn, req, transport).await,
+                    _ => self.handle_other_request(conn, req, transport).await,
+                }
+            }
         }
-        Ok(())
     }
 
-    async fn handle_connect_request(&self, mut conn: TcpStream, req: Request) -> Result<()> {
+    async fn handle_connect_request(&self, mut conn: ClientStream, req: Request, transport: &Transport) -> Result<()> {
         let (host, port) = host_port_from_req(&req)?;
         let upstream_addr = format!("{}:{}", host, port);
         log::debug!("try to connect to {}", upstream_addr);
-        let upstream = match self.transport.connect(&upstream_addr).await {
+        let mut upstream = match transport.connect(host, port, self.pool.as_ref()).await {
             Ok(stream) => {
                 stream
             }
@@ -75,16 +177,20 @@ impl HttpProxy {
             }
         };
         log::debug!("connected to {}", upstream_addr);
+        if let Some(version) = self.proxy_protocol {
+            write_proxy_protocol_header(&mut upstream, version, conn.peer_addr()?, upstream.peer_addr()?).await?;
+            log::debug!("sent PROXY protocol header to upstream");
+        }
         // send back response to notify client the proxy initialization succeeds
         // follow rfc7231#section-4.3.6: do not send Content-Length header
         conn.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await?;
         log::debug!("send CONNECT response 200 to client");
-    
+
         // forward two streams
         keep_alive_proxy(conn, upstream).await
     }
 
-    async fn handle_other_request(&self, mut conn: TcpStream, mut req: Request) -> Result<()> {
+    async fn handle_other_request(&self, mut conn: ClientStream, mut req: Request, transport: &Transport) -> Result<()> {
         let (host, port) = host_port_from_req(&req)?;
         let keep_alive = if let Some(pc) = req.header("Proxy-Connection") {
             pc == "Keep-Alive"
@@ -93,7 +199,7 @@ impl HttpProxy {
         };
         let upstream_addr = format!("{}:{}", host, port);
         log::debug!("try to connect to {}", upstream_addr);
-        let mut upstream = match self.transport.connect(&upstream_addr).await {
+        let mut upstream = match transport.connect(host, port, self.pool.as_ref()).await {
             Ok(stream) => {
                 stream
             }
@@ -106,6 +212,10 @@ impl HttpProxy {
             }
         };
         log::debug!("connected to {}", upstream_addr);
+        if let Some(version) = self.proxy_protocol {
+            write_proxy_protocol_header(&mut upstream, version, conn.peer_addr()?, upstream.peer_addr()?).await?;
+            log::debug!("sent PROXY protocol header to upstream");
+        }
         req.remove_header("Proxy-Connection");
         if keep_alive {
             log::debug!("keep-alive enabled on upstream connection");
@@ -118,14 +228,32 @@ impl HttpProxy {
             keep_alive_proxy(conn, upstream).await?;
             return Ok(());
         }
-        // not keep-alive, send and close connection
-        req.insert_header("Connection", "close");
+        // not keep-alive from the client's perspective, but when a pool is
+        // configured, ask the upstream to keep its end open anyway so the
+        // now-idle connection can be pooled for the next request to this host.
+        // with a PROXY protocol header in use, nothing is pooled: the header
+        // must lead a fresh connection and can't be sent again on a reused one
+        let pooled_upstream = self.pool.as_ref()
+            .filter(|_| self.proxy_protocol.is_none())
+            .map(|_| upstream.clone());
+        if pooled_upstream.is_some() {
+            req.insert_header("Connection", "keep-alive");
+        } else {
+            req.insert_header("Connection", "close");
+        }
         let req_method = req.method();
         let mut resp = client::connect(upstream, req).await?;
         log::debug!("original response={:#?}", resp);
+        let upstream_closed = resp.header("Connection")
+            .map(|v| v == "close")
+            .unwrap_or(false);
         resp.insert_header("Connection", "close");
         let encoder = server::Encoder::new(resp, req_method);
         io::copy(encoder, &mut conn).await?;
+        if let (Some(pool), false, Some(UpstreamStream::Tcp(stream))) = (&self.pool, upstream_closed, pooled_upstream) {
+            log::debug!("returning upstream connection to {} to pool", upstream_addr);
+            pool.put(upstream_addr, stream).await;
+        }
         Ok(())
     }
 }
@@ -133,24 +261,123 @@ impl HttpProxy {
 #[derive(Debug)]
 pub enum Transport {
     Direct,
-    Socks5(String),
+    Socks5{ addr: String, auth: Option<socks5::client::Credentials>, remote_resolve: bool },
+    /// carry the upstream connection over KCP (a reliable ARQ protocol on UDP), via
+    /// a relay listening at this address, instead of a raw TCP connection
+    Kcp(String),
 }
 
 impl Transport {
-    pub async fn connect(&self, target: impl AsyncToSocketAddrs) -> Result<TcpStream> {
+    /// connect to `host:port` over this transport, reusing a pooled connection
+    /// for the same destination when `pool` is given and one is available
+    pub async fn connect(&self, host: &str, port: u16, pool: Option<&Pool>) -> Result<UpstreamStream> {
+        if let Some(pool) = pool {
+            if !matches!(self, Transport::Kcp(_)) {
+                let key = format!("{}:{}", host, port);
+                if let Some(stream) = pool.take(&key).await {
+                    log::debug!("reusing pooled connection to {}", key);
+                    return Ok(UpstreamStream::Tcp(stream));
+                }
+            }
+        }
         let conn = match self {
             Transport::Direct => {
-                TcpStream::connect(target).await?
+                UpstreamStream::Tcp(TcpStream::connect((host, port)).await?)
+            }
+            Transport::Socks5{ addr, auth, remote_resolve } => {
+                UpstreamStream::Tcp(socks5::client::proxy(addr, host, port, auth.as_ref(), *remote_resolve).await?)
             }
-            Transport::Socks5(proxy) => {
-                socks5::client::proxy(proxy, target).await?
+            Transport::Kcp(relay_addr) => {
+                UpstreamStream::Kcp(crate::kcp::connect(relay_addr, host, port).await?)
             }
         };
         Ok(conn)
     }
 }
 
-async fn keep_alive_proxy(conn: TcpStream, upstream: TcpStream) -> Result<()> {
+/// PROXY protocol version used to announce the real client address to the upstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+/// write a PROXY protocol header carrying `src` (the client) and `dst` (the upstream)
+/// to the upstream stream, before any request bytes are forwarded. falls back to
+/// the protocol's "unknown connection" encoding when `src` and `dst` are from
+/// different address families (e.g. an IPv4 listener proxying to an IPv6-only
+/// upstream), rather than failing the request
+async fn write_proxy_protocol_header(upstream: &mut UpstreamStream, version: ProxyProtocolVersion, src: SocketAddr, dst: SocketAddr) -> Result<()> {
+    match version {
+        ProxyProtocolVersion::V1 => {
+            let line = match (src, dst) {
+                (SocketAddr::V4(src), SocketAddr::V4(dst)) => {
+                    format!("PROXY TCP4 {} {} {} {}\r\n", src.ip(), dst.ip(), src.port(), dst.port())
+                }
+                (SocketAddr::V6(src), SocketAddr::V6(dst)) => {
+                    format!("PROXY TCP6 {} {} {} {}\r\n", src.ip(), dst.ip(), src.port(), dst.port())
+                }
+                // mismatched families can't be expressed as TCP4/TCP6: fall back
+                // to the spec's UNKNOWN line, which carries no addresses
+                _ => "PROXY UNKNOWN\r\n".to_owned(),
+            };
+            upstream.write_all(line.as_bytes()).await?;
+        }
+        ProxyProtocolVersion::V2 => {
+            let mut header = Vec::with_capacity(28);
+            header.extend_from_slice(&[0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A]);
+            header.push(0x21); // version 2, PROXY command
+            match (src, dst) {
+                (SocketAddr::V4(src), SocketAddr::V4(dst)) => {
+                    header.push(0x11); // AF_INET, STREAM
+                    header.extend_from_slice(&12u16.to_be_bytes());
+                    header.extend_from_slice(&src.ip().octets());
+                    header.extend_from_slice(&dst.ip().octets());
+                    header.extend_from_slice(&src.port().to_be_bytes());
+                    header.extend_from_slice(&dst.port().to_be_bytes());
+                }
+                (SocketAddr::V6(src), SocketAddr::V6(dst)) => {
+                    header.push(0x21); // AF_INET6, STREAM
+                    header.extend_from_slice(&36u16.to_be_bytes());
+                    header.extend_from_slice(&src.ip().octets());
+                    header.extend_from_slice(&dst.ip().octets());
+                    header.extend_from_slice(&src.port().to_be_bytes());
+                    header.extend_from_slice(&dst.port().to_be_bytes());
+                }
+                // mismatched families: AF_UNSPEC, no address block, per spec §2.2
+                _ => {
+                    header.push(0x00);
+                    header.extend_from_slice(&0u16.to_be_bytes());
+                }
+            }
+            upstream.write_all(&header).await?;
+        }
+    }
+    Ok(())
+}
+
+/// loop the client's own bytes back without connecting anywhere, for hosts
+/// routed to [`Upstream::Echo`]
+async fn echo(mut conn: ClientStream, req: Request) -> Result<()> {
+    if req.method() == Method::Connect {
+        conn.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await?;
+    }
+    let mut writer = conn.clone();
+    let copied = io::copy(conn, &mut writer).await?;
+    log::debug!("echoed {} bytes back to client", copied);
+    Ok(())
+}
+
+/// forward bytes in both directions until one side closes.
+///
+/// this is a bare byte tunnel with no HTTP framing once it starts (raw CONNECT
+/// tunnels, and the "Proxy-Connection: Keep-Alive" forwarding path alike), and
+/// `future::select` drops whichever copy direction loses the race without
+/// draining it — so there is no reliable way to tell the upstream side is
+/// actually idle and safe to hand to an unrelated future request. Never pool
+/// connections out of here; only the single-request/response path in
+/// `handle_other_request` pools, where the exchange is known to be complete
+async fn keep_alive_proxy(conn: ClientStream, upstream: UpstreamStream) -> Result<()> {
     let mut conn_writer = conn.clone();
     let mut upstream_writer = upstream.clone();
     let proxy_result = future::select(
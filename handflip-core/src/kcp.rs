@@ -0,0 +1,194 @@
+use async_net::{AsyncToSocketAddrs, UdpSocket, resolve};
+use crate::error::{Result, Error};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::{select, AsyncWriteExt, FutureExt, StreamExt};
+use smol::{channel, Timer};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MTU: usize = 1400;
+const UPDATE_INTERVAL: Duration = Duration::from_millis(10);
+
+static NEXT_CONV: AtomicU32 = AtomicU32::new(1);
+
+/// dial a KCP relay at `relay_addr`, tell it which `host:port` to forward the
+/// session to, and return a reliable, ordered stream layered on top of it,
+/// suitable for forwarding an upstream TCP connection over a lossy/high-latency
+/// UDP path
+pub async fn connect(relay_addr: impl AsyncToSocketAddrs, host: &str, port: u16) -> Result<KcpStream> {
+    let relay = resolve(relay_addr).await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::BadRequest("unknown kcp relay host".to_owned()))?;
+    let dst = resolve((host, port)).await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::BadRequest(format!("unknown upstream host {}", host)))?;
+    let bind_addr = if relay.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(relay).await?;
+    let conv = NEXT_CONV.fetch_add(1, Ordering::Relaxed);
+
+    let (out_tx, out_rx) = channel::unbounded::<Vec<u8>>();
+    let (app_write_tx, app_write_rx) = channel::unbounded::<Vec<u8>>();
+    let (app_read_tx, app_read_rx) = channel::unbounded::<Vec<u8>>();
+
+    let mut kcp = kcp::Kcp::new(conv, KcpOutput{ tx: out_tx });
+    kcp.set_nodelay(true, 10, 2, true);
+    kcp.set_wndsize(256, 256);
+
+    smol::spawn(drive(kcp, socket, out_rx, app_write_rx, app_read_tx)).detach();
+
+    let mut stream = KcpStream{
+        dst,
+        app_write_tx,
+        app_read_rx,
+        pending: Vec::new(),
+    };
+    write_target_handshake(&mut stream, host, port).await?;
+    Ok(stream)
+}
+
+/// tell the relay which destination to forward this session to: a single
+/// length-prefixed host string followed by a 2-byte big-endian port,
+/// analogous to the address encoding in [`crate::socks5::client::send`]
+async fn write_target_handshake(stream: &mut KcpStream, host: &str, port: u16) -> Result<()> {
+    let host = host.as_bytes();
+    let mut frame = Vec::with_capacity(1 + host.len() + 2);
+    frame.push(host.len() as u8);
+    frame.extend_from_slice(host);
+    frame.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&frame).await?;
+    Ok(())
+}
+
+/// feeds the raw bytes KCP wants to send out as UDP datagrams into `out_tx`,
+/// picked up by [`drive`] and written to the socket
+struct KcpOutput {
+    tx: channel::Sender<Vec<u8>>,
+}
+
+impl io::Write for KcpOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx.try_send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "kcp relay closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// drives a single KCP session: ticks its internal clock, shuttles datagrams
+/// between the UDP socket and the KCP core, and relays decoded application
+/// bytes to/from the owning [`KcpStream`]
+async fn drive(
+    mut kcp: kcp::Kcp<KcpOutput>,
+    socket: UdpSocket,
+    out_rx: channel::Receiver<Vec<u8>>,
+    app_write_rx: channel::Receiver<Vec<u8>>,
+    app_read_tx: channel::Sender<Vec<u8>>,
+) {
+    let mut ticker = Timer::interval(UPDATE_INTERVAL);
+    let mut datagram = [0u8; MTU];
+    loop {
+        select! {
+            _ = ticker.next().fuse() => {
+                let _ = kcp.update(now_ms());
+            }
+            received = socket.recv(&mut datagram).fuse() => {
+                let n = match received {
+                    Ok(n) => n,
+                    Err(_) => break,
+                };
+                if kcp.input(&datagram[..n]).is_err() {
+                    continue;
+                }
+                // size the buffer to each whole message, which can span many
+                // datagrams, so recv never rejects it as too large
+                while let Ok(size) = kcp.peeksize() {
+                    let mut recv_buf = vec![0u8; size];
+                    let n = match kcp.recv(&mut recv_buf) {
+                        Ok(n) => n,
+                        Err(_) => break,
+                    };
+                    if app_read_tx.send(recv_buf[..n].to_vec()).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            outgoing = app_write_rx.recv().fuse() => {
+                match outgoing {
+                    Ok(data) => { let _ = kcp.send(&data); }
+                    Err(_) => break,
+                }
+            }
+            payload = out_rx.recv().fuse() => {
+                match payload {
+                    Ok(payload) => { let _ = socket.send(&payload).await; }
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+}
+
+fn now_ms() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u32)
+        .unwrap_or(0)
+}
+
+/// a reliable, ordered stream over a KCP session carried on UDP
+#[derive(Clone)]
+pub struct KcpStream {
+    /// the real upstream destination this session was asked to forward to,
+    /// not the relay's own address
+    dst: SocketAddr,
+    app_write_tx: channel::Sender<Vec<u8>>,
+    app_read_rx: channel::Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl KcpStream {
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.dst
+    }
+}
+
+impl AsyncRead for KcpStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if self.pending.is_empty() {
+            match Pin::new(&mut self.app_read_rx).poll_next(cx) {
+                Poll::Ready(Some(chunk)) => self.pending = chunk,
+                Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for KcpStream {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.app_write_tx.try_send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "kcp relay closed"))?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,56 @@
+use async_net::TcpStream;
+use smol::lock::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// caches idle upstream TCP connections keyed by `host:port`, so repeated
+/// short-lived tunnels to the same destination can skip a fresh handshake
+#[derive(Debug, Clone)]
+pub struct Pool {
+    idle: Arc<Mutex<HashMap<String, Vec<Idle>>>>,
+    max_idle: usize,
+    idle_timeout: Duration,
+}
+
+#[derive(Debug)]
+struct Idle {
+    stream: TcpStream,
+    since: Instant,
+}
+
+impl Pool {
+    pub fn new(max_idle: usize, idle_timeout: Duration) -> Self {
+        Self{
+            idle: Arc::new(Mutex::new(HashMap::new())),
+            max_idle,
+            idle_timeout,
+        }
+    }
+
+    /// take a still-fresh pooled connection for `key`, if one is available.
+    /// connections that sat idle past the timeout are dropped along the way
+    pub async fn take(&self, key: &str) -> Option<TcpStream> {
+        let mut idle = self.idle.lock().await;
+        let conns = idle.get_mut(key)?;
+        while let Some(Idle{ stream, since }) = conns.pop() {
+            if since.elapsed() < self.idle_timeout {
+                return Some(stream);
+            }
+        }
+        None
+    }
+
+    /// return a connection to the pool for reuse, dropping it instead if the
+    /// pool for `key` is already at its configured capacity
+    pub async fn put(&self, key: String, stream: TcpStream) {
+        if self.max_idle == 0 {
+            return;
+        }
+        let mut idle = self.idle.lock().await;
+        let conns = idle.entry(key).or_insert_with(Vec::new);
+        if conns.len() < self.max_idle {
+            conns.push(Idle{ stream, since: Instant::now() });
+        }
+    }
+}
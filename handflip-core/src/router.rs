@@ -0,0 +1,117 @@
+use crate::http::Transport;
+use crate::error::{Result, Error};
+use std::fs;
+use std::path::Path;
+
+/// where to send a request, chosen per-destination by [`Router`]
+#[derive(Debug)]
+pub enum Upstream {
+    /// refuse the request outright
+    Ban,
+    /// loop the client's own bytes back without connecting anywhere
+    Echo,
+    /// forward the request through the given transport
+    Proxy(Transport),
+}
+
+/// matches a request's destination host against a set of rules, loaded from
+/// a config file, to choose an [`Upstream`]
+#[derive(Debug)]
+pub struct Router {
+    rules: Vec<(Pattern, Upstream)>,
+    pub(crate) default: Upstream,
+}
+
+#[derive(Debug)]
+enum Pattern {
+    Exact(String),
+    /// suffix after the leading "*.", e.g. "example.com" for "*.example.com"
+    WildcardSuffix(String),
+}
+
+impl Pattern {
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            Pattern::Exact(exact) => exact == host,
+            Pattern::WildcardSuffix(suffix) => host == suffix || host.ends_with(&format!(".{}", suffix)),
+        }
+    }
+}
+
+impl Router {
+    /// a router with no rules, sending every host to `default`
+    pub fn new(default: Upstream) -> Self {
+        Self{ rules: Vec::new(), default }
+    }
+
+    /// choose the upstream for `host`, preferring the first matching rule
+    /// (in file order) and falling back to the router's default
+    pub fn resolve(&self, host: &str) -> &Upstream {
+        self.rules.iter()
+            .find(|(pattern, _)| pattern.matches(host))
+            .map(|(_, upstream)| upstream)
+            .unwrap_or(&self.default)
+    }
+
+    /// load per-host rules from a config file, adding them after any
+    /// rules already on this router
+    ///
+    /// each non-empty, non-`#`-comment line has the form `pattern = upstream`,
+    /// where `pattern` is an exact hostname, a wildcard suffix like
+    /// `*.example.com`, or the literal `default`, and `upstream` is one of
+    /// `ban`, `echo`, `direct`, `socks5:<addr>`, or `kcp:<addr>`. a
+    /// `default = upstream` line overrides the router's fallback instead of
+    /// adding a rule.
+    pub fn load_rules(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (pattern, upstream) = line.split_once('=')
+                .ok_or_else(|| Error::Parse(format!("invalid router rule {:?}, expect pattern = upstream", line)))?;
+            let pattern = pattern.trim();
+            let upstream = parse_upstream(upstream.trim())?;
+            if pattern == "default" {
+                self.default = merge_default(self.default, upstream);
+            } else if let Some(suffix) = pattern.strip_prefix("*.") {
+                self.rules.push((Pattern::WildcardSuffix(suffix.to_owned()), upstream));
+            } else {
+                self.rules.push((Pattern::Exact(pattern.to_owned()), upstream));
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// combine a new default upstream loaded from the config file with the
+/// previous one, carrying over SOCKS5 auth/remote-resolve settings (e.g. from
+/// `--socks5-auth`/`--socks5-remote-resolve`) that the config file format has
+/// no syntax to express, so a `default = socks5:<addr>` line doesn't silently
+/// drop them
+fn merge_default(old: Upstream, new: Upstream) -> Upstream {
+    match (old, new) {
+        (Upstream::Proxy(Transport::Socks5{ auth, remote_resolve, .. }), Upstream::Proxy(Transport::Socks5{ addr, .. })) => {
+            Upstream::Proxy(Transport::Socks5{ addr, auth, remote_resolve })
+        }
+        (_, new) => new,
+    }
+}
+
+fn parse_upstream(spec: &str) -> Result<Upstream> {
+    match spec {
+        "ban" => Ok(Upstream::Ban),
+        "echo" => Ok(Upstream::Echo),
+        "direct" => Ok(Upstream::Proxy(Transport::Direct)),
+        _ if spec.starts_with("socks5:") => {
+            let addr = &spec["socks5:".len()..];
+            Ok(Upstream::Proxy(Transport::Socks5{ addr: addr.to_owned(), auth: None, remote_resolve: false }))
+        }
+        _ if spec.starts_with("kcp:") => {
+            let addr = &spec["kcp:".len()..];
+            Ok(Upstream::Proxy(Transport::Kcp(addr.to_owned())))
+        }
+        _ => Err(Error::Parse(format!("invalid upstream {:?}, expect ban, echo, direct, socks5:<addr>, or kcp:<addr>", spec))),
+    }
+}
@@ -1,43 +1,111 @@
 use async_net::{AsyncToSocketAddrs, TcpStream, resolve};
 use crate::error::{Result, Error};
 use futures::{AsyncWriteExt, AsyncReadExt};
-use std::net::{SocketAddr, SocketAddrV4};
+use std::net::{IpAddr, SocketAddr};
 
-pub async fn proxy(proxy_addr: impl AsyncToSocketAddrs, target_addr: impl AsyncToSocketAddrs) -> Result<TcpStream> {
-    let target_addrs = resolve(target_addr).await?;
-    let target_addr = target_addrs.into_iter().filter_map(|addr| match addr {
-        SocketAddr::V4(v4) => Some(v4),
-        SocketAddr::V6(_) => None,
-    }).next()
-        .ok_or_else(|| Error::BadRequest("unknown host".to_owned()))?;
-    log::debug!("resolve host to {:?}", target_addr);
+/// connect to `host`:`port` through the SOCKS5 proxy at `proxy_addr`.
+///
+/// when `remote_resolve` is set, `host` is forwarded to the proxy as a domain name
+/// (address type `0x03`) and the proxy performs DNS resolution itself; otherwise
+/// `host` is resolved locally (or parsed directly, if it is already an IP literal)
+/// and sent as an IPv4 or IPv6 address.
+pub async fn proxy(proxy_addr: impl AsyncToSocketAddrs, host: &str, port: u16, auth: Option<&Credentials>, remote_resolve: bool) -> Result<TcpStream> {
+    let addr = if remote_resolve {
+        log::debug!("forwarding domain {} to proxy for remote resolution", host);
+        Addr::DomainName(host.to_owned())
+    } else if let Ok(ip) = host.parse::<IpAddr>() {
+        match ip {
+            IpAddr::V4(v4) => Addr::IPv4(v4.octets()),
+            IpAddr::V6(v6) => Addr::IPv6(v6.octets()),
+        }
+    } else {
+        let resolved = resolve((host, port)).await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::BadRequest("unknown host".to_owned()))?;
+        log::debug!("resolve host to {:?}", resolved);
+        match resolved {
+            SocketAddr::V4(v4) => Addr::IPv4(v4.ip().octets()),
+            SocketAddr::V6(v6) => Addr::IPv6(v6.ip().octets()),
+        }
+    };
     let mut conn = TcpStream::connect(proxy_addr).await?;
     log::debug!("connected to proxy addr");
-    handshake(&mut conn).await?;
+    handshake(&mut conn, auth).await?;
     log::debug!("handshake succeeded");
-    send(&mut conn, target_addr).await?;
+    send(&mut conn, addr, port).await?;
     log::debug!("send succeeded");
     receive(&mut conn).await?;
     log::debug!("receive succeeded");
     Ok(conn)
 }
 
-async fn handshake(conn: &mut TcpStream) -> Result<()> {
-    // version=5, methods=1, method=no auth
-    let req = [5u8, 1, 0];
+/// username/password credentials for RFC 1929 sub-negotiation
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+async fn handshake(conn: &mut TcpStream, auth: Option<&Credentials>) -> Result<()> {
+    // version=5, offer no-auth, and username/password when credentials are configured
+    let methods: &[u8] = if auth.is_some() { &[0x00, 0x02] } else { &[0x00] };
+    let mut req = vec![5u8, methods.len() as u8];
+    req.extend_from_slice(methods);
     conn.write_all(&req[..]).await?;
     log::debug!("send negotiation request to server {:?}", req);
     let mut buf = [0u8;2];
     conn.read_exact(&mut buf).await?;
     log::debug!("receive negotiation response from server {:?}", buf);
+    match buf[1] {
+        0x00 => Ok(()),
+        0x02 => {
+            let auth = auth.ok_or_else(|| Error::Server("server requires username/password authentication".to_owned()))?;
+            authenticate(conn, auth).await
+        }
+        0xFF => Err(Error::Server("no acceptable authentication methods".to_owned())),
+        other => Err(Error::Server(format!("server selected unsupported method {}", other))),
+    }
+}
+
+async fn authenticate(conn: &mut TcpStream, auth: &Credentials) -> Result<()> {
+    // RFC 1929: version=1, username, password
+    let username = auth.username.as_bytes();
+    let password = auth.password.as_bytes();
+    let mut req = vec![1u8, username.len() as u8];
+    req.extend_from_slice(username);
+    req.push(password.len() as u8);
+    req.extend_from_slice(password);
+    conn.write_all(&req[..]).await?;
+    log::debug!("send username/password authentication request to server");
+    let mut buf = [0u8;2];
+    conn.read_exact(&mut buf).await?;
+    if buf[1] != 0x00 {
+        return Err(Error::Server(format!("username/password authentication failed with status {}", buf[1])));
+    }
+    log::debug!("username/password authentication succeeded");
     Ok(())
 }
 
-async fn send(conn: &mut TcpStream, addr: SocketAddrV4) -> Result<()> {
-    // version=5, cmd=connect, reserve=0, addr_type=ipv4
-    let mut req = vec![5u8, 1, 0, 1];
-    req.extend_from_slice(&addr.ip().octets());
-    req.extend_from_slice(&addr.port().to_be_bytes());
+async fn send(conn: &mut TcpStream, addr: Addr, port: u16) -> Result<()> {
+    // version=5, cmd=connect, reserve=0
+    let mut req = vec![5u8, 1, 0];
+    match &addr {
+        Addr::IPv4(octets) => {
+            req.push(0x01);
+            req.extend_from_slice(octets);
+        }
+        Addr::IPv6(octets) => {
+            req.push(0x04);
+            req.extend_from_slice(octets);
+        }
+        Addr::DomainName(domain) => {
+            req.push(0x03);
+            req.push(domain.len() as u8);
+            req.extend_from_slice(domain.as_bytes());
+        }
+    }
+    req.extend_from_slice(&port.to_be_bytes());
     conn.write_all(&req[..]).await?;
     Ok(())
 }
@@ -0,0 +1,109 @@
+use async_net::TcpStream;
+use crate::kcp::KcpStream;
+use crate::tls::TlsClientStream;
+use futures::io::{AsyncRead, AsyncWrite};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// an upstream connection, either a plain TCP socket or a KCP session carried
+/// over UDP. lets [`crate::http::Transport::connect`] return one type
+/// regardless of which transport was used, so the request-handling code
+/// doesn't need to care which one it got
+#[derive(Clone)]
+pub enum UpstreamStream {
+    Tcp(TcpStream),
+    Kcp(KcpStream),
+}
+
+impl UpstreamStream {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            UpstreamStream::Tcp(stream) => stream.peer_addr(),
+            UpstreamStream::Kcp(stream) => Ok(stream.peer_addr()),
+        }
+    }
+}
+
+impl AsyncRead for UpstreamStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            UpstreamStream::Kcp(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for UpstreamStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            UpstreamStream::Kcp(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            UpstreamStream::Kcp(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            UpstreamStream::Tcp(stream) => Pin::new(stream).poll_close(cx),
+            UpstreamStream::Kcp(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+}
+
+/// a client-facing connection, either plaintext or TLS-terminated. lets
+/// [`crate::http::HttpProxy`] serve both plaintext HTTP and HTTPS listeners
+/// through the same request-handling code
+#[derive(Clone)]
+pub enum ClientStream {
+    Plain(TcpStream),
+    Tls(TlsClientStream),
+}
+
+impl ClientStream {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            ClientStream::Plain(stream) => stream.peer_addr(),
+            ClientStream::Tls(stream) => Ok(stream.peer_addr()),
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_close(cx),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use async_net::TcpStream;
+use async_dup::Mutex;
+use crate::error::{Result, Error};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures_rustls::{rustls, TlsAcceptor};
+use futures_rustls::server::TlsStream;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// terminates TLS on accepted client connections, so proxy credentials and
+/// CONNECT targets never travel in the clear on the local hop
+#[derive(Clone)]
+pub struct TlsListener {
+    acceptor: TlsAcceptor,
+}
+
+impl std::fmt::Debug for TlsListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TlsListener{..}")
+    }
+}
+
+impl TlsListener {
+    /// load a PEM certificate chain and a PEM private key from disk
+    pub fn from_files(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> Result<Self> {
+        let config = server_config_from_files(cert_path, key_path)?;
+        Ok(Self{ acceptor: TlsAcceptor::from(Arc::new(config)) })
+    }
+
+    /// a self-signed certificate generated at startup, for local testing only
+    pub fn with_generated_cert() -> Result<Self> {
+        let config = generated_server_config()?;
+        Ok(Self{ acceptor: TlsAcceptor::from(Arc::new(config)) })
+    }
+
+    pub async fn accept(&self, conn: TcpStream) -> Result<TlsClientStream> {
+        let peer = conn.peer_addr()?;
+        let stream = self.acceptor.accept(conn).await?;
+        Ok(TlsClientStream{ peer, inner: async_dup::Arc::new(Mutex::new(stream)) })
+    }
+}
+
+fn server_config_from_files(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> Result<rustls::ServerConfig> {
+    let certs = load_certs(cert_path)?;
+    let key = load_key(key_path)?;
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| Error::Parse(format!("invalid TLS certificate/key: {}", e)))
+}
+
+fn load_certs(path: impl AsRef<Path>) -> Result<Vec<rustls::Certificate>> {
+    let mut reader = io::BufReader::new(std::fs::File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .map_err(|e| Error::Parse(format!("invalid certificate PEM: {}", e)))?;
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+fn load_key(path: impl AsRef<Path>) -> Result<rustls::PrivateKey> {
+    let mut reader = io::BufReader::new(std::fs::File::open(path)?);
+    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut reader)
+        .map_err(|e| Error::Parse(format!("invalid private key PEM: {}", e)))?;
+    let key = keys.pop()
+        .ok_or_else(|| Error::Parse("no private key found in key file".to_owned()))?;
+    Ok(rustls::PrivateKey(key))
+}
+
+/// a fresh, ephemeral self-signed certificate. never committed to disk, so
+/// there is no shared private key for anyone to reuse across deployments
+fn generated_server_config() -> Result<rustls::ServerConfig> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()])
+        .map_err(|e| Error::Parse(format!("failed to generate self-signed certificate: {}", e)))?;
+    let cert_der = cert.serialize_der()
+        .map_err(|e| Error::Parse(format!("failed to serialize self-signed certificate: {}", e)))?;
+    let key_der = cert.serialize_private_key_der();
+    rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(vec![rustls::Certificate(cert_der)], rustls::PrivateKey(key_der))
+        .map_err(|e| Error::Parse(format!("invalid generated TLS certificate: {}", e)))
+}
+
+/// a TLS session with a client, wrapped so it can be cheaply cloned and
+/// shared the same way a [`TcpStream`] is throughout the request-handling code
+#[derive(Clone)]
+pub struct TlsClientStream {
+    peer: SocketAddr,
+    inner: async_dup::Arc<Mutex<TlsStream<TcpStream>>>,
+}
+
+impl TlsClientStream {
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+}
+
+impl AsyncRead for TlsClientStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsClientStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}